@@ -0,0 +1,232 @@
+//! A read-only dictionary backed by a sorted, flat key-value file.
+//!
+//! This intentionally does not implement the DJB `cdb` constant-database
+//! hash format (no `cdb` crate is vendored in this tree); instead entries
+//! are kept sorted by syllable key so a lookup can binary search and a
+//! prefix query can bound a forward scan, which is the property system
+//! dictionaries actually need from this backend.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::zhuyin::{Syllable, SyllableSlice};
+
+use super::{
+    kv::{decode_syllables, encode_syllables},
+    BuildDictionaryError, DictEntries, Dictionary, DictionaryBuilder, DictionaryInfo,
+    DictionaryUpdateError, Phrase,
+};
+
+/// The error type returned by [`CdbDictionary`] operations.
+#[derive(Error, Debug)]
+#[error("cdb dictionary error")]
+pub struct CdbDictionaryError {
+    #[from]
+    source: io::Error,
+}
+
+/// A read-only system dictionary backed by a sorted key-value file.
+#[derive(Debug, Clone)]
+pub struct CdbDictionary {
+    info: DictionaryInfo,
+    entries: Vec<(Vec<Syllable>, Phrase)>,
+}
+
+impl CdbDictionary {
+    /// Opens a dictionary previously written by
+    /// [`CdbDictionaryBuilder::build`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CdbDictionary, CdbDictionaryError> {
+        let file = File::open(path)?;
+        let mut lines = BufReader::new(file).lines();
+        let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+        let info = read_info_line(&header);
+
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let syllables = decode_syllables(fields.next().unwrap_or_default())?;
+            let phrase_str = fields.next().unwrap_or_default().to_owned();
+            let freq = fields.next().unwrap_or_default().parse().unwrap_or(0);
+            let last_used = fields
+                .next()
+                .filter(|time| !time.is_empty())
+                .and_then(|time| time.parse().ok());
+            let mut phrase = Phrase::new(phrase_str, freq);
+            if let Some(time) = last_used {
+                phrase = phrase.with_time(time);
+            }
+            entries.push((syllables, phrase));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(CdbDictionary { info, entries })
+    }
+
+    fn prefix_range(&self, prefix: &[Syllable]) -> &[(Vec<Syllable>, Phrase)] {
+        let start = self.entries.partition_point(|(key, _)| key.as_slice() < prefix);
+        let end = start
+            + self.entries[start..]
+                .iter()
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .count();
+        &self.entries[start..end]
+    }
+}
+
+fn read_info_line(line: &str) -> DictionaryInfo {
+    let mut fields = line.split('\t');
+    let mut next_field = || fields.next().filter(|value| !value.is_empty()).map(str::to_owned);
+    DictionaryInfo {
+        name: next_field(),
+        copyright: next_field(),
+        license: next_field(),
+        version: next_field(),
+        software: next_field(),
+    }
+}
+
+fn read_only_error() -> DictionaryUpdateError {
+    DictionaryUpdateError {
+        source: Some(Box::new(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "CdbDictionary is read-only; rebuild it with CdbDictionaryBuilder instead",
+        ))),
+    }
+}
+
+impl Dictionary for CdbDictionary {
+    fn lookup_first_n_phrases(&self, syllables: &dyn SyllableSlice, first: usize) -> Vec<Phrase> {
+        let syllables = syllables.as_slice().into_owned();
+        let start = self
+            .entries
+            .partition_point(|(key, _)| key < &syllables);
+        let mut phrases: Vec<Phrase> = self.entries[start..]
+            .iter()
+            .take_while(|(key, _)| key == &syllables)
+            .map(|(_, phrase)| phrase.clone())
+            .collect();
+        phrases.sort_by(|a, b| b.cmp(a));
+        phrases.truncate(first);
+        phrases
+    }
+
+    fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let prefix = prefix.as_slice().into_owned();
+        let mut matches: Vec<_> = self.prefix_range(&prefix).to_vec();
+        matches.sort_by(|a, b| b.1.cmp(&a.1));
+        matches.truncate(first);
+        matches
+    }
+
+    fn entries(&self) -> Option<DictEntries<'_>> {
+        Some(Box::new(self.entries.clone().into_iter()))
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.info.clone()
+    }
+
+    fn reopen(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn add_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase: Phrase,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+
+    fn update_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase: Phrase,
+        _user_freq: u32,
+        _time: u64,
+        _prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+
+    fn remove_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase_str: &str,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+}
+
+/// Builds a [`CdbDictionary`] file from a sequence of phrase insertions.
+#[derive(Debug, Default)]
+pub struct CdbDictionaryBuilder {
+    info: DictionaryInfo,
+    entries: Vec<(Vec<Syllable>, Phrase)>,
+}
+
+impl CdbDictionaryBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> CdbDictionaryBuilder {
+        CdbDictionaryBuilder::default()
+    }
+}
+
+impl DictionaryBuilder for CdbDictionaryBuilder {
+    fn set_info(&mut self, info: DictionaryInfo) -> Result<(), BuildDictionaryError> {
+        self.info = info;
+        Ok(())
+    }
+
+    fn insert(&mut self, syllables: &[Syllable], phrase: Phrase) -> Result<(), BuildDictionaryError> {
+        self.entries.push((syllables.to_vec(), phrase));
+        Ok(())
+    }
+
+    fn build(&mut self, path: &Path) -> Result<(), BuildDictionaryError> {
+        self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            self.info.name.as_deref().unwrap_or_default(),
+            self.info.copyright.as_deref().unwrap_or_default(),
+            self.info.license.as_deref().unwrap_or_default(),
+            self.info.version.as_deref().unwrap_or_default(),
+            self.info.software.as_deref().unwrap_or_default(),
+        )?;
+        for (syllables, phrase) in &self.entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                encode_syllables(syllables),
+                phrase.as_str(),
+                phrase.freq(),
+                phrase
+                    .last_used()
+                    .map(|time| time.to_string())
+                    .unwrap_or_default(),
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+//! Shared syllable-sequence encoding for the file-backed dictionary
+//! backends.
+//!
+//! Every backend that persists `(syllables, phrase)` pairs as text
+//! (`trie_buf`'s append log, [`super::trie`], [`super::cdb`]) encodes the
+//! syllable key the same way `Dictionary::export` does: space-separated
+//! bopomofo. Keeping a single convention means a record copied between any
+//! of these files round-trips without a translation step.
+
+use std::io;
+
+use crate::zhuyin::Syllable;
+
+/// Encodes a syllable sequence as space-separated bopomofo.
+pub(crate) fn encode_syllables(syllables: &[Syllable]) -> String {
+    syllables
+        .iter()
+        .map(Syllable::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The inverse of [`encode_syllables`].
+pub(crate) fn decode_syllables(field: &str) -> io::Result<Vec<Syllable>> {
+    field
+        .split(' ')
+        .filter(|syllable| !syllable.is_empty())
+        .map(|syllable| {
+            syllable
+                .parse::<Syllable>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "corrupt syllable record"))
+        })
+        .collect()
+}
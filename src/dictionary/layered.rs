@@ -0,0 +1,187 @@
+//! Combines multiple dictionaries (e.g. a base system dictionary and a
+//! user dictionary) behind a single [`Dictionary`] view.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::zhuyin::{Syllable, SyllableSlice};
+
+use super::{DictEntries, Dictionary, DictionaryInfo, DictionaryUpdateError, Phrase};
+
+/// A stack of dictionaries queried together, highest-precedence last.
+///
+/// Lookups merge results from every layer, de-duplicating by
+/// `(syllables, phrase)` and keeping the highest frequency seen for each.
+/// Writes always go to the last (topmost) layer, which is conventionally
+/// the writable user dictionary.
+#[derive(Debug)]
+pub struct LayeredDictionary {
+    dicts: Vec<Box<dyn Dictionary>>,
+}
+
+impl LayeredDictionary {
+    /// Creates a new layered view over `dicts`, ordered from lowest to
+    /// highest precedence.
+    pub fn new(dicts: Vec<Box<dyn Dictionary>>) -> LayeredDictionary {
+        LayeredDictionary { dicts }
+    }
+
+    fn writable_layer(&mut self) -> Result<&mut Box<dyn Dictionary>, DictionaryUpdateError> {
+        self.dicts.last_mut().ok_or(DictionaryUpdateError {
+            source: Some(Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "layered dictionary has no layers to write to",
+            ))),
+        })
+    }
+
+    /// De-duplicates candidates by `(syllables, phrase)`, keeping the
+    /// highest frequency seen for each pair and the most recent
+    /// `last_used` seen across all of them.
+    ///
+    /// The key includes `syllables`, not just the phrase string, so two
+    /// layers that agree on a phrase but disagree on its pronunciation
+    /// (heteronyms, e.g. 行 as both `xíng` and `háng`) both survive the
+    /// merge instead of one silently shadowing the other.
+    fn merge_unordered(
+        candidates: impl Iterator<Item = (Vec<Syllable>, Phrase)>,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let mut index = HashMap::new();
+        let mut merged: Vec<(Vec<Syllable>, Phrase)> = Vec::new();
+        for (syllables, phrase) in candidates {
+            match index.entry((syllables.clone(), phrase.as_str().to_owned())) {
+                Entry::Occupied(entry) => {
+                    let i: usize = *entry.get();
+                    let last_used = merged[i].1.last_used().max(phrase.last_used());
+                    if phrase.freq() > merged[i].1.freq() {
+                        merged[i] = (syllables, phrase);
+                    }
+                    if let Some(time) = last_used {
+                        merged[i].1 = merged[i].1.clone().with_time(time);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(merged.len());
+                    merged.push((syllables, phrase));
+                }
+            }
+        }
+        merged
+    }
+
+    /// Flattens every layer into `target`, de-duplicating the same way
+    /// lookups do, and upserts each merged entry via [`Dictionary::update_phrase`].
+    ///
+    /// Useful for consolidating a system-plus-user layer stack into one
+    /// writable backend, e.g. migrating old layers' contributions into the
+    /// user dictionary that's about to replace them.
+    pub fn merge_into(&self, target: &mut dyn Dictionary) -> Result<(), DictionaryUpdateError> {
+        for (syllables, phrase) in Self::merge_unordered(self.entries().into_iter().flatten()) {
+            let freq = phrase.freq();
+            let last_used = phrase.last_used().unwrap_or(0);
+            target.update_phrase(&syllables, phrase, freq, last_used, None)?;
+        }
+        Ok(())
+    }
+}
+
+impl Dictionary for LayeredDictionary {
+    fn lookup_first_n_phrases(&self, syllables: &dyn SyllableSlice, first: usize) -> Vec<Phrase> {
+        let syllables = syllables.as_slice().into_owned();
+        let candidates = self.dicts.iter().flat_map(|dict| {
+            dict.lookup_all_phrases(&syllables)
+                .into_iter()
+                .map(|phrase| (syllables.clone(), phrase))
+        });
+        let mut merged = Self::merge_unordered(candidates);
+        merged.sort_by(|a, b| b.1.cmp(&a.1));
+        merged.truncate(first);
+        merged.into_iter().map(|(_, phrase)| phrase).collect()
+    }
+
+    fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let candidates = self
+            .dicts
+            .iter()
+            .flat_map(|dict| dict.lookup_phrases_by_prefix(prefix, usize::MAX));
+        let mut merged = Self::merge_unordered(candidates);
+        merged.sort_by(|a, b| b.1.cmp(&a.1));
+        merged.truncate(first);
+        merged
+    }
+
+    fn entries(&self) -> Option<DictEntries<'_>> {
+        let mut entries = Vec::new();
+        for dict in &self.dicts {
+            let Some(dict_entries) = dict.entries() else {
+                continue;
+            };
+            entries.extend(dict_entries);
+        }
+        Some(Box::new(Self::merge_unordered(entries.into_iter()).into_iter()))
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.dicts
+            .last()
+            .map(|dict| dict.about())
+            .unwrap_or_default()
+    }
+
+    fn reopen(&mut self) -> Result<(), DictionaryUpdateError> {
+        for dict in &mut self.dicts {
+            dict.reopen()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), DictionaryUpdateError> {
+        for dict in &mut self.dicts {
+            dict.flush()?;
+        }
+        Ok(())
+    }
+
+    fn add_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase: Phrase,
+    ) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?.add_phrase(syllables, phrase)
+    }
+
+    fn update_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase: Phrase,
+        user_freq: u32,
+        time: u64,
+        prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?
+            .update_phrase(syllables, phrase, user_freq, time, prev_phrase)
+    }
+
+    fn remove_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase_str: &str,
+    ) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?.remove_phrase(syllables, phrase_str)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?.commit_transaction()
+    }
+
+    fn abort_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.writable_layer()?.abort_transaction()
+    }
+}
@@ -6,6 +6,7 @@ use std::{
     cmp::Ordering,
     collections::HashMap,
     fmt::{Debug, Display},
+    io::{self, BufRead, Write},
     path::Path,
 };
 
@@ -258,7 +259,7 @@ impl Display for Phrase {
 pub type Phrases<'a> = Box<dyn Iterator<Item = Phrase> + 'a>;
 
 /// TODO: doc
-pub type DictEntries = Box<dyn Iterator<Item = (Vec<Syllable>, Phrase)>>;
+pub type DictEntries<'a> = Box<dyn Iterator<Item = (Vec<Syllable>, Phrase)> + 'a>;
 
 /// An interface for looking up dictionaries.
 ///
@@ -306,10 +307,58 @@ pub trait Dictionary: Any + Debug {
     fn lookup_all_phrases(&self, syllables: &dyn SyllableSlice) -> Vec<Phrase> {
         self.lookup_first_n_phrases(syllables, usize::MAX)
     }
+    /// Returns the first N phrases whose syllable sequence begins with
+    /// `prefix`, ordered by frequency.
+    ///
+    /// This is the primitive behind autocomplete/continuation hints: once
+    /// the user has typed the first few syllables of a phrase, longer
+    /// stored phrases that continue from there can be suggested.
+    ///
+    /// Backends that cannot serve this efficiently may rely on the default
+    /// implementation, which returns no results.
+    fn lookup_phrases_by_prefix(
+        &self,
+        _prefix: &dyn SyllableSlice,
+        _first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        Vec::new()
+    }
     /// Returns an iterator to all phrases in the dictionary.
     ///
     /// Some dictionary backend does not support this operation.
-    fn entries(&self) -> Option<DictEntries>;
+    fn entries(&self) -> Option<DictEntries<'_>>;
+    /// Writes every entry in this dictionary to `writer` as a stable,
+    /// line-oriented TSV: syllables (bopomofo, space separated), phrase,
+    /// frequency, and the optional last-used time, one entry per line.
+    ///
+    /// This gives a backend-agnostic backup/migration format; pair it with
+    /// [`import_phrases`] and a [`DictionaryBuilder`] to load it back.
+    /// Backends that return `None` from [`Dictionary::entries`] export
+    /// nothing.
+    fn export(&self, writer: &mut dyn Write) -> Result<(), DictionaryUpdateError> {
+        let Some(entries) = self.entries() else {
+            return Ok(());
+        };
+        for (syllables, phrase) in entries {
+            let syllables = kv::encode_syllables(&syllables);
+            let last_used = phrase
+                .last_used()
+                .map(|time| time.to_string())
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                syllables,
+                phrase.as_str(),
+                phrase.freq(),
+                last_used
+            )
+            .map_err(|err| DictionaryUpdateError {
+                source: Some(Box::new(err)),
+            })?;
+        }
+        Ok(())
+    }
     /// Returns information about the dictionary instance.
     fn about(&self) -> DictionaryInfo;
     /// Reopens the dictionary if it was changed by a different process
@@ -351,12 +400,18 @@ pub trait Dictionary: Any + Debug {
     ) -> Result<(), DictionaryUpdateError>;
 
     /// TODO: doc
+    ///
+    /// `prev_phrase`, when known, is the phrase committed immediately
+    /// before this one. Backends that also implement [`BigramDictionary`]
+    /// should use it to record/increment that bigram's count; backends
+    /// that don't track bigrams are free to ignore it.
     fn update_phrase(
         &mut self,
         syllables: &dyn SyllableSlice,
         phrase: Phrase,
         user_freq: u32,
         time: u64,
+        prev_phrase: Option<&str>,
     ) -> Result<(), DictionaryUpdateError>;
 
     /// TODO: doc
@@ -365,6 +420,102 @@ pub trait Dictionary: Any + Debug {
         syllables: &dyn SyllableSlice,
         phrase_str: &str,
     ) -> Result<(), DictionaryUpdateError>;
+
+    /// Begins a batch of updates that can be rolled back as a group.
+    ///
+    /// Subsequent calls to `add_phrase`, `update_phrase`, and
+    /// `remove_phrase` are staged rather than immediately persisted, until
+    /// [`Dictionary::commit_transaction`] or [`Dictionary::abort_transaction`]
+    /// is called. This lets a bulk import discard every change it made so
+    /// far as soon as one of them fails (e.g. a [`DuplicatePhraseError`]),
+    /// instead of leaving the dictionary half-modified.
+    ///
+    /// The default implementation is a no-op, which is correct for
+    /// read-only backends and for backends where every update is already
+    /// atomic.
+    fn begin_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+    /// Persists every update made since [`Dictionary::begin_transaction`].
+    fn commit_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+    /// Discards every update made since [`Dictionary::begin_transaction`].
+    fn abort_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+}
+
+/// An interface for looking up context-dependent (bigram) phrase counts.
+///
+/// Implementing this alongside [`Dictionary`] lets candidate ranking take
+/// the previously committed phrase into account, instead of relying only
+/// on the unigram frequency recorded in [`Phrase::freq`]. Pass an
+/// implementation to [`score_phrases_with_bigram`] to reorder a candidate
+/// list accordingly.
+pub trait BigramDictionary {
+    /// Returns the phrases matched by `syllables` together with how many
+    /// times each one was committed immediately after `prev_phrase`.
+    ///
+    /// The result should use a stable order each time for the same input.
+    fn lookup_bigram(&self, prev_phrase: &str, syllables: &dyn SyllableSlice) -> Vec<Phrase>;
+
+    /// Returns `count(prev_phrase)`: how many times *any* phrase was
+    /// committed immediately after `prev_phrase`, across every syllable
+    /// context, not just the one passed to [`Self::lookup_bigram`].
+    ///
+    /// This is the denominator `score_phrases_with_bigram` uses for
+    /// `P_bi`; it must not be derived by summing [`Self::lookup_bigram`]'s
+    /// result for a single `syllables`, which only covers the candidates
+    /// for that one context.
+    fn bigram_total(&self, prev_phrase: &str) -> u32;
+}
+
+/// Default interpolation weight used by [`score_phrases_with_bigram`].
+///
+/// Candidates lean more heavily on the bigram probability than the
+/// unigram one, while still falling back gracefully when the bigram is
+/// unseen.
+pub const DEFAULT_BIGRAM_LAMBDA: f64 = 0.7;
+
+/// Reorders `candidates` in place by an interpolated bigram/unigram score.
+///
+/// For each candidate the score is `λ · P_bi + (1 − λ) · P_uni`, where
+/// `P_uni = freq / total_unigram_count` and `P_bi = count(prev, cand) /
+/// count(prev)`, with `count(prev)` drawn from [`BigramDictionary::bigram_total`].
+/// Candidates with an unseen bigram get `P_bi = 0` and fall back to their
+/// plain unigram score. `lambda` is typically [`DEFAULT_BIGRAM_LAMBDA`].
+pub fn score_phrases_with_bigram(
+    dict: &dyn BigramDictionary,
+    prev_phrase: &str,
+    syllables: &dyn SyllableSlice,
+    candidates: &mut [Phrase],
+    total_unigram_count: u32,
+    lambda: f64,
+) {
+    let bigrams = dict.lookup_bigram(prev_phrase, syllables);
+    let prev_count = dict.bigram_total(prev_phrase);
+    let bigram_freq = |phrase: &str| -> u32 {
+        bigrams
+            .iter()
+            .find(|candidate| candidate.as_str() == phrase)
+            .map(Phrase::freq)
+            .unwrap_or(0)
+    };
+    let score = |phrase: &Phrase| -> f64 {
+        let p_uni = if total_unigram_count == 0 {
+            0.0
+        } else {
+            phrase.freq() as f64 / total_unigram_count as f64
+        };
+        let p_bi = if prev_count == 0 {
+            0.0
+        } else {
+            bigram_freq(phrase.as_str()) as f64 / prev_count as f64
+        };
+        lambda * p_bi + (1.0 - lambda) * p_uni
+    };
+    candidates.sort_by(|a, b| score(b).partial_cmp(&score(a)).unwrap_or(Ordering::Equal));
 }
 
 /// TODO: doc
@@ -397,6 +548,51 @@ pub trait DictionaryBuilder {
     fn build(&mut self, path: &Path) -> Result<(), BuildDictionaryError>;
 }
 
+/// Reads entries previously written by [`Dictionary::export`] and feeds
+/// them into `builder`, line by line.
+///
+/// This is the counterpart to [`Dictionary::export`]; together they make
+/// it possible to migrate or back up a dictionary independently of its
+/// backend (trie, sqlite, cdb, ...). Blank lines are skipped; any other
+/// malformed line is reported as a [`BuildDictionaryError`].
+pub fn import_phrases(
+    builder: &mut dyn DictionaryBuilder,
+    reader: impl BufRead,
+) -> Result<(), BuildDictionaryError> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let syllables = kv::decode_syllables(fields.next().ok_or_else(|| malformed_entry(&line))?)
+            .map_err(|_| malformed_entry(&line))?;
+        let phrase_str = fields.next().ok_or_else(|| malformed_entry(&line))?;
+        let freq = fields
+            .next()
+            .and_then(|freq| freq.parse().ok())
+            .ok_or_else(|| malformed_entry(&line))?;
+        let phrase = match fields.next().filter(|time| !time.is_empty()) {
+            Some(time) => {
+                let time = time.parse().map_err(|_| malformed_entry(&line))?;
+                Phrase::new(phrase_str, freq).with_time(time)
+            }
+            None => Phrase::new(phrase_str, freq),
+        };
+        builder.insert(&syllables, phrase)?;
+    }
+    Ok(())
+}
+
+fn malformed_entry(line: &str) -> BuildDictionaryError {
+    BuildDictionaryError {
+        source: Box::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed dictionary export entry: {line:?}"),
+        )),
+    }
+}
+
 impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase>> {
     fn lookup_first_n_phrases(&self, syllables: &dyn SyllableSlice, first: usize) -> Vec<Phrase> {
         let syllables = dbg!(syllables.as_slice().into_owned());
@@ -405,7 +601,7 @@ impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase>> {
         dbg!(phrases)
     }
 
-    fn entries(&self) -> Option<DictEntries> {
+    fn entries(&self) -> Option<DictEntries<'_>> {
         Some(Box::new(self.clone().into_iter().flat_map(|(k, v)| {
             v.into_iter().map(move |phrase| (k.clone(), phrase.clone()))
         })))
@@ -445,6 +641,7 @@ impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase>> {
         _phrase: Phrase,
         _user_freq: u32,
         _time: u64,
+        _prev_phrase: Option<&str>,
     ) -> Result<(), DictionaryUpdateError> {
         Ok(())
     }
@@ -469,9 +666,40 @@ impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase>> {
 mod tests {
     use std::collections::HashMap;
 
-    use crate::{dictionary::Phrase, syl, zhuyin::Bopomofo::*};
+    use crate::{dictionary::Phrase, syl, zhuyin::Bopomofo::*, zhuyin::SyllableSlice};
 
-    use super::Dictionary;
+    use super::{
+        import_phrases, score_phrases_with_bigram, BigramDictionary, Dictionary,
+        DictionaryBuilder, TrieDictionary, TrieDictionaryBuilder,
+    };
+
+    /// A `prev_phrase -> (syllables, phrase)` bigram table, with
+    /// `bigram_total` tracked independently of any single syllable
+    /// context, the way a persisted backend would.
+    struct FakeBigramDict {
+        counts: HashMap<String, Vec<(Vec<crate::zhuyin::Syllable>, Phrase)>>,
+        totals: HashMap<String, u32>,
+    }
+
+    impl BigramDictionary for FakeBigramDict {
+        fn lookup_bigram(&self, prev_phrase: &str, syllables: &dyn SyllableSlice) -> Vec<Phrase> {
+            let syllables = syllables.as_slice().into_owned();
+            self.counts
+                .get(prev_phrase)
+                .map(|candidates| {
+                    candidates
+                        .iter()
+                        .filter(|(context, _)| *context == syllables)
+                        .map(|(_, phrase)| phrase.clone())
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+
+        fn bigram_total(&self, prev_phrase: &str) -> u32 {
+            self.totals.get(prev_phrase).copied().unwrap_or(0)
+        }
+    }
 
     #[test]
     fn hashmap_lookup_first_one() {
@@ -504,4 +732,61 @@ mod tests {
             dict.lookup_all_phrases(&[syl![C, E, TONE4], syl![SH, TONE4]])
         )
     }
+
+    #[test]
+    fn bigram_score_uses_dicts_total_not_context_sum() {
+        let syllables = [syl![C, E, TONE4], syl![SH, TONE4]];
+        // "測試" was seen once after "你好" in this context, but "你好" was
+        // followed by 99 other things overall: bigram_total must reflect
+        // that 100, not just the 1 visible through lookup_bigram for this
+        // one syllable context.
+        let dict = FakeBigramDict {
+            counts: HashMap::from([(
+                "你好".to_string(),
+                vec![(syllables.to_vec(), Phrase::new("測試", 1))],
+            )]),
+            totals: HashMap::from([("你好".to_string(), 100)]),
+        };
+
+        let mut candidates = vec![Phrase::new("測試", 1), Phrase::new("策試", 2)];
+        score_phrases_with_bigram(&dict, "你好", &syllables, &mut candidates, 3, 0.7);
+
+        // P_bi("測試") = 1/100 = 0.01, P_uni("測試") = 1/3
+        // score("測試") = 0.7*0.01 + 0.3*(1/3) = 0.107
+        // P_bi("策試") = 0 (unseen), P_uni("策試") = 2/3
+        // score("策試") = 0.3*(2/3) = 0.2
+        // Summing the context-local bigram freq as the total (1, not 100)
+        // would instead score "測試" 0.7 + 0.1 = 0.8 and keep it first.
+        assert_eq!(
+            vec![Phrase::new("策試", 2), Phrase::new("測試", 1)],
+            candidates
+        );
+    }
+
+    #[test]
+    fn export_then_import_round_trips_through_kv_encoding() {
+        let dict = HashMap::from([(
+            vec![syl![C, E, TONE4], syl![SH, TONE4]],
+            vec![Phrase::new("測試", 1).with_time(42)],
+        )]);
+
+        let mut buf = Vec::new();
+        dict.export(&mut buf).unwrap();
+
+        let mut builder = TrieDictionaryBuilder::new();
+        import_phrases(&mut builder, buf.as_slice()).unwrap();
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("roundtrip.dat");
+        builder.build(&path).unwrap();
+
+        let reopened = TrieDictionary::open(&path).unwrap();
+        assert_eq!(
+            vec![(
+                vec![syl![C, E, TONE4], syl![SH, TONE4]],
+                Phrase::new("測試", 1).with_time(42)
+            )],
+            reopened.entries().into_iter().flatten().collect::<Vec<_>>()
+        );
+    }
 }
\ No newline at end of file
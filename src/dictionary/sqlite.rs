@@ -0,0 +1,352 @@
+//! A dictionary backend persisted in a SQLite database.
+
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use thiserror::Error;
+
+use crate::zhuyin::{Syllable, SyllableSlice};
+
+use super::{
+    kv::{decode_syllables, encode_syllables},
+    BigramDictionary, BuildDictionaryError, DictEntries, Dictionary, DictionaryBuilder,
+    DictionaryInfo, DictionaryUpdateError, Phrase,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS info (
+        name TEXT, copyright TEXT, license TEXT, version TEXT, software TEXT
+    );
+    CREATE TABLE IF NOT EXISTS phrases (
+        syllables TEXT NOT NULL,
+        phrase TEXT NOT NULL,
+        freq INTEGER NOT NULL,
+        last_used INTEGER,
+        PRIMARY KEY (syllables, phrase)
+    );
+    CREATE INDEX IF NOT EXISTS phrases_by_syllables ON phrases (syllables);
+    CREATE TABLE IF NOT EXISTS bigrams (
+        prev_phrase TEXT NOT NULL,
+        syllables TEXT NOT NULL,
+        phrase TEXT NOT NULL,
+        freq INTEGER NOT NULL,
+        PRIMARY KEY (prev_phrase, syllables, phrase)
+    );
+    CREATE INDEX IF NOT EXISTS bigrams_by_prev_phrase ON bigrams (prev_phrase);
+";
+
+/// The error type returned by [`SqliteDictionary`] operations.
+#[derive(Error, Debug)]
+#[error("sqlite dictionary error")]
+pub struct SqliteDictionaryError {
+    #[from]
+    source: rusqlite::Error,
+}
+
+/// A dictionary backend persisted in a SQLite database, with transactions
+/// backed by SQLite's own `BEGIN`/`COMMIT`/`ROLLBACK`.
+#[derive(Debug)]
+pub struct SqliteDictionary {
+    conn: Connection,
+}
+
+impl SqliteDictionary {
+    /// Opens (creating if necessary) a SQLite-backed dictionary at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteDictionary, SqliteDictionaryError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteDictionary { conn })
+    }
+
+    fn row_to_phrase(phrase: String, freq: u32, last_used: Option<u64>) -> Phrase {
+        match last_used {
+            Some(time) => Phrase::new(phrase, freq).with_time(time),
+            None => Phrase::new(phrase, freq),
+        }
+    }
+}
+
+impl BigramDictionary for SqliteDictionary {
+    fn lookup_bigram(&self, prev_phrase: &str, syllables: &dyn SyllableSlice) -> Vec<Phrase> {
+        let key = encode_syllables(&syllables.as_slice().into_owned());
+        let mut stmt = match self.conn.prepare(
+            "SELECT phrase, freq FROM bigrams WHERE prev_phrase = ?1 AND syllables = ?2",
+        ) {
+            Ok(stmt) => stmt,
+            Err(_) => return Vec::new(),
+        };
+        let Ok(rows) = stmt.query_map(params![prev_phrase, key], |row| {
+            Ok(Phrase::new(row.get::<_, String>(0)?, row.get(1)?))
+        }) else {
+            return Vec::new();
+        };
+        rows.filter_map(Result::ok).collect()
+    }
+
+    fn bigram_total(&self, prev_phrase: &str) -> u32 {
+        self.conn
+            .query_row(
+                "SELECT COALESCE(SUM(freq), 0) FROM bigrams WHERE prev_phrase = ?1",
+                params![prev_phrase],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+    }
+}
+
+impl Dictionary for SqliteDictionary {
+    fn lookup_first_n_phrases(&self, syllables: &dyn SyllableSlice, first: usize) -> Vec<Phrase> {
+        let key = encode_syllables(&syllables.as_slice().into_owned());
+        let mut stmt = match self
+            .conn
+            .prepare("SELECT phrase, freq, last_used FROM phrases WHERE syllables = ?1 ORDER BY freq DESC")
+        {
+            Ok(stmt) => stmt,
+            Err(_) => return Vec::new(),
+        };
+        let Ok(rows) = stmt.query_map(params![key], |row| {
+            Ok(Self::row_to_phrase(row.get(0)?, row.get(1)?, row.get(2)?))
+        }) else {
+            return Vec::new();
+        };
+        rows.filter_map(Result::ok).take(first).collect()
+    }
+
+    fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let prefix = prefix.as_slice().into_owned();
+        let key = encode_syllables(&prefix);
+        // Tone 1 omits its mark, so one syllable's encoded text can be a
+        // literal prefix of an unrelated syllable's (e.g. "ㄇㄚ" vs "ㄇㄚˊ").
+        // The LIKE pattern is only a coarse, index-friendly over-fetch;
+        // `starts_with` on the decoded syllable sequence below is what
+        // actually decides prefix membership.
+        let like_pattern = format!("{key}%");
+        let mut stmt = match self.conn.prepare(
+            "SELECT syllables, phrase, freq, last_used FROM phrases \
+             WHERE syllables = ?1 OR syllables LIKE ?2 ORDER BY freq DESC",
+        ) {
+            Ok(stmt) => stmt,
+            Err(_) => return Vec::new(),
+        };
+        let Ok(rows) = stmt.query_map(params![key, like_pattern], |row| {
+            let syllables: String = row.get(0)?;
+            Ok((syllables, row.get(1)?, row.get(2)?, row.get(3)?))
+        }) else {
+            return Vec::new();
+        };
+        rows.filter_map(Result::ok)
+            .filter_map(|(syllables, phrase, freq, last_used)| {
+                let syllables = decode_syllables(&syllables).ok()?;
+                syllables.starts_with(prefix.as_slice()).then_some(())?;
+                Some((syllables, Self::row_to_phrase(phrase, freq, last_used)))
+            })
+            .take(first)
+            .collect()
+    }
+
+    fn entries(&self) -> Option<DictEntries<'_>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT syllables, phrase, freq, last_used FROM phrases ORDER BY syllables, phrase")
+            .ok()?;
+        let entries: Vec<_> = stmt
+            .query_map([], |row| {
+                let syllables: String = row.get(0)?;
+                Ok((syllables, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?))
+            })
+            .ok()?
+            .filter_map(Result::ok)
+            .filter_map(|(syllables, phrase, freq, last_used)| {
+                let syllables = decode_syllables(&syllables).ok()?;
+                Some((syllables, Self::row_to_phrase(phrase, freq, last_used)))
+            })
+            .collect();
+        Some(Box::new(entries.into_iter()))
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.conn
+            .query_row(
+                "SELECT name, copyright, license, version, software FROM info LIMIT 1",
+                [],
+                |row| {
+                    Ok(DictionaryInfo {
+                        name: row.get(0)?,
+                        copyright: row.get(1)?,
+                        license: row.get(2)?,
+                        version: row.get(3)?,
+                        software: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    fn reopen(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn add_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase: Phrase,
+    ) -> Result<(), DictionaryUpdateError> {
+        let key = encode_syllables(&syllables.as_slice().into_owned());
+        self.conn
+            .execute(
+                "INSERT INTO phrases (syllables, phrase, freq, last_used) VALUES (?1, ?2, ?3, ?4)",
+                params![key, phrase.as_str(), phrase.freq(), phrase.last_used()],
+            )
+            .map_err(|err| DictionaryUpdateError {
+                source: Some(Box::new(SqliteDictionaryError::from(err))),
+            })?;
+        Ok(())
+    }
+
+    fn update_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase: Phrase,
+        user_freq: u32,
+        time: u64,
+        prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        let key = encode_syllables(&syllables.as_slice().into_owned());
+        self.conn
+            .execute(
+                "INSERT INTO phrases (syllables, phrase, freq, last_used) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (syllables, phrase) DO UPDATE SET freq = ?3, last_used = ?4",
+                params![key, phrase.as_str(), user_freq, time],
+            )
+            .map_err(|err| DictionaryUpdateError {
+                source: Some(Box::new(SqliteDictionaryError::from(err))),
+            })?;
+        if let Some(prev_phrase) = prev_phrase {
+            self.conn
+                .execute(
+                    "INSERT INTO bigrams (prev_phrase, syllables, phrase, freq) VALUES (?1, ?2, ?3, 1)
+                     ON CONFLICT (prev_phrase, syllables, phrase) DO UPDATE SET freq = freq + 1",
+                    params![prev_phrase, key, phrase.as_str()],
+                )
+                .map_err(|err| DictionaryUpdateError {
+                    source: Some(Box::new(SqliteDictionaryError::from(err))),
+                })?;
+        }
+        Ok(())
+    }
+
+    fn remove_phrase(
+        &mut self,
+        syllables: &dyn SyllableSlice,
+        phrase_str: &str,
+    ) -> Result<(), DictionaryUpdateError> {
+        let key = encode_syllables(&syllables.as_slice().into_owned());
+        self.conn
+            .execute(
+                "DELETE FROM phrases WHERE syllables = ?1 AND phrase = ?2",
+                params![key, phrase_str],
+            )
+            .map_err(|err| DictionaryUpdateError {
+                source: Some(Box::new(SqliteDictionaryError::from(err))),
+            })?;
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.conn.execute_batch("BEGIN").map_err(|err| DictionaryUpdateError {
+            source: Some(Box::new(SqliteDictionaryError::from(err))),
+        })
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.conn.execute_batch("COMMIT").map_err(|err| DictionaryUpdateError {
+            source: Some(Box::new(SqliteDictionaryError::from(err))),
+        })
+    }
+
+    fn abort_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.conn.execute_batch("ROLLBACK").map_err(|err| DictionaryUpdateError {
+            source: Some(Box::new(SqliteDictionaryError::from(err))),
+        })
+    }
+}
+
+/// Builds a [`SqliteDictionary`] file from a sequence of phrase insertions.
+#[derive(Debug)]
+pub struct SqliteDictionaryBuilder {
+    conn: Connection,
+}
+
+impl SqliteDictionaryBuilder {
+    /// Creates a builder backed by a fresh in-memory database; call
+    /// [`DictionaryBuilder::build`] to persist it to `path`.
+    pub fn new() -> SqliteDictionaryBuilder {
+        let conn = Connection::open_in_memory().expect("failed to open in-memory sqlite database");
+        conn.execute_batch(SCHEMA)
+            .expect("failed to initialize sqlite schema");
+        SqliteDictionaryBuilder { conn }
+    }
+
+}
+
+impl Default for SqliteDictionaryBuilder {
+    fn default() -> Self {
+        SqliteDictionaryBuilder::new()
+    }
+}
+
+impl DictionaryBuilder for SqliteDictionaryBuilder {
+    fn set_info(&mut self, info: DictionaryInfo) -> Result<(), BuildDictionaryError> {
+        self.conn
+            .execute("DELETE FROM info", [])
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        self.conn
+            .execute(
+                "INSERT INTO info (name, copyright, license, version, software) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![info.name, info.copyright, info.license, info.version, info.software],
+            )
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        Ok(())
+    }
+
+    fn insert(&mut self, syllables: &[Syllable], phrase: Phrase) -> Result<(), BuildDictionaryError> {
+        let key = encode_syllables(syllables);
+        self.conn
+            .execute(
+                "INSERT INTO phrases (syllables, phrase, freq, last_used) VALUES (?1, ?2, ?3, ?4)",
+                params![key, phrase.as_str(), phrase.freq(), phrase.last_used()],
+            )
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        Ok(())
+    }
+
+    fn build(&mut self, path: &Path) -> Result<(), BuildDictionaryError> {
+        let dest = Connection::open(path)
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        dest.execute_batch(SCHEMA)
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        self.conn
+            .execute("ATTACH DATABASE ?1 AS dest", params![path.to_string_lossy()])
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        self.conn
+            .execute_batch(
+                "INSERT INTO dest.info SELECT * FROM info;
+                 INSERT INTO dest.phrases SELECT * FROM phrases;
+                 INSERT INTO dest.bigrams SELECT * FROM bigrams;
+                 DETACH DATABASE dest;",
+            )
+            .map_err(|err| BuildDictionaryError::from(Box::new(err) as Box<dyn std::error::Error + Send + Sync>))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! A read-only, file-backed dictionary backed by an in-memory prefix tree.
+//!
+//! Phrases are grouped by syllable sequence in a trie so that both exact
+//! lookups and [`Dictionary::lookup_phrases_by_prefix`] can descend straight
+//! to the relevant subtree instead of scanning every entry.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::zhuyin::{Syllable, SyllableSlice};
+
+use super::{
+    kv::{decode_syllables, encode_syllables},
+    BigramDictionary, BuildDictionaryError, DictEntries, Dictionary, DictionaryBuilder,
+    DictionaryInfo, DictionaryUpdateError, Phrase,
+};
+
+/// Marks the start of the bigram section in a [`TrieDictionary`] file, after
+/// the phrase lines written by [`TrieDictionaryBuilder::build`].
+const BIGRAM_SECTION_MARKER: &str = "#bigrams";
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: BTreeMap<Syllable, TrieNode>,
+    phrases: Vec<Phrase>,
+}
+
+impl TrieNode {
+    fn child_mut(&mut self, syllable: Syllable) -> &mut TrieNode {
+        self.children.entry(syllable).or_default()
+    }
+
+    fn get(&self, syllables: &[Syllable]) -> Option<&TrieNode> {
+        match syllables.split_first() {
+            None => Some(self),
+            Some((head, tail)) => self.children.get(head).and_then(|child| child.get(tail)),
+        }
+    }
+
+    fn collect_entries(&self, path: &mut Vec<Syllable>, out: &mut Vec<(Vec<Syllable>, Phrase)>) {
+        for phrase in &self.phrases {
+            out.push((path.clone(), phrase.clone()));
+        }
+        for (syllable, child) in &self.children {
+            path.push(*syllable);
+            child.collect_entries(path, out);
+            path.pop();
+        }
+    }
+}
+
+/// Summary statistics about a [`TrieDictionary`], useful for diagnostics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrieDictionaryStatistics {
+    /// Number of distinct syllable-sequence nodes in the trie.
+    pub node_count: usize,
+    /// Number of phrases stored across all nodes.
+    pub phrase_count: usize,
+}
+
+/// A system or user dictionary backed by an in-memory trie and persisted to
+/// a single file.
+#[derive(Debug, Clone)]
+pub struct TrieDictionary {
+    info: DictionaryInfo,
+    root: TrieNode,
+    /// Bigram records keyed by the preceding phrase, each paired with the
+    /// syllable context it was committed under.
+    bigrams: HashMap<String, Vec<(Vec<Syllable>, Phrase)>>,
+    /// `count(prev)` for each preceding phrase: the sum of every bigram
+    /// freq recorded for it, across all syllable contexts.
+    bigram_totals: HashMap<String, u32>,
+}
+
+impl TrieDictionary {
+    /// Opens a trie dictionary previously written by
+    /// [`TrieDictionaryBuilder::build`].
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<TrieDictionary> {
+        let file = File::open(path)?;
+        let mut lines = BufReader::new(file).lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "missing dictionary header"))??;
+        let info = read_info_line(&header);
+
+        let mut root = TrieNode::default();
+        let mut bigrams: HashMap<String, Vec<(Vec<Syllable>, Phrase)>> = HashMap::new();
+        let mut bigram_totals: HashMap<String, u32> = HashMap::new();
+        let mut in_bigram_section = false;
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            if line == BIGRAM_SECTION_MARKER {
+                in_bigram_section = true;
+                continue;
+            }
+
+            if in_bigram_section {
+                let mut fields = line.split('\t');
+                let prev_phrase = fields.next().unwrap_or_default().to_owned();
+                let syllables = decode_syllables(fields.next().unwrap_or_default())?;
+                let phrase_str = fields.next().unwrap_or_default().to_owned();
+                let freq: u32 = fields.next().unwrap_or_default().parse().unwrap_or(0);
+                *bigram_totals.entry(prev_phrase.clone()).or_default() += freq;
+                bigrams
+                    .entry(prev_phrase)
+                    .or_default()
+                    .push((syllables, Phrase::new(phrase_str, freq)));
+                continue;
+            }
+
+            let mut fields = line.split('\t');
+            let syllables = decode_syllables(fields.next().unwrap_or_default())?;
+            let phrase_str = fields.next().unwrap_or_default().to_owned();
+            let freq = fields.next().unwrap_or_default().parse().unwrap_or(0);
+            let last_used = fields
+                .next()
+                .filter(|time| !time.is_empty())
+                .and_then(|time| time.parse().ok());
+
+            let mut node = &mut root;
+            for syllable in &syllables {
+                node = node.child_mut(*syllable);
+            }
+            let mut phrase = Phrase::new(phrase_str, freq);
+            if let Some(time) = last_used {
+                phrase = phrase.with_time(time);
+            }
+            node.phrases.push(phrase);
+        }
+
+        Ok(TrieDictionary {
+            info,
+            root,
+            bigrams,
+            bigram_totals,
+        })
+    }
+
+    /// Returns the persisted bigram entries for `prev_phrase`, across every
+    /// syllable context it was recorded under.
+    pub(crate) fn bigram_entries(&self, prev_phrase: &str) -> Vec<(Vec<Syllable>, Phrase)> {
+        self.bigrams.get(prev_phrase).cloned().unwrap_or_default()
+    }
+
+    /// Returns every persisted bigram record, across every preceding
+    /// phrase, for a full rebuild that needs to fold them back in.
+    pub(crate) fn all_bigrams(&self) -> Vec<(String, Vec<Syllable>, Phrase)> {
+        self.bigrams
+            .iter()
+            .flat_map(|(prev_phrase, entries)| {
+                entries
+                    .iter()
+                    .map(move |(syllables, phrase)| (prev_phrase.clone(), syllables.clone(), phrase.clone()))
+            })
+            .collect()
+    }
+
+    /// Returns node/phrase counts for diagnostics.
+    pub fn statistics(&self) -> TrieDictionaryStatistics {
+        fn walk(node: &TrieNode, stats: &mut TrieDictionaryStatistics) {
+            stats.node_count += 1;
+            stats.phrase_count += node.phrases.len();
+            for child in node.children.values() {
+                walk(child, stats);
+            }
+        }
+        let mut stats = TrieDictionaryStatistics::default();
+        walk(&self.root, &mut stats);
+        stats
+    }
+}
+
+impl BigramDictionary for TrieDictionary {
+    fn lookup_bigram(&self, prev_phrase: &str, syllables: &dyn SyllableSlice) -> Vec<Phrase> {
+        let syllables = syllables.as_slice().into_owned();
+        self.bigrams
+            .get(prev_phrase)
+            .map(|candidates| {
+                candidates
+                    .iter()
+                    .filter(|(context, _)| *context == syllables)
+                    .map(|(_, phrase)| phrase.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn bigram_total(&self, prev_phrase: &str) -> u32 {
+        self.bigram_totals.get(prev_phrase).copied().unwrap_or(0)
+    }
+}
+
+fn read_info_line(line: &str) -> DictionaryInfo {
+    let mut fields = line.split('\t');
+    let mut next_field = || fields.next().filter(|value| !value.is_empty()).map(str::to_owned);
+    DictionaryInfo {
+        name: next_field(),
+        copyright: next_field(),
+        license: next_field(),
+        version: next_field(),
+        software: next_field(),
+    }
+}
+
+fn write_info_line(writer: &mut impl Write, info: &DictionaryInfo) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}",
+        info.name.as_deref().unwrap_or_default(),
+        info.copyright.as_deref().unwrap_or_default(),
+        info.license.as_deref().unwrap_or_default(),
+        info.version.as_deref().unwrap_or_default(),
+        info.software.as_deref().unwrap_or_default(),
+    )
+}
+
+fn read_only_error() -> DictionaryUpdateError {
+    DictionaryUpdateError {
+        source: Some(Box::new(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "TrieDictionary is read-only; rebuild it with TrieDictionaryBuilder instead",
+        ))),
+    }
+}
+
+impl Dictionary for TrieDictionary {
+    fn lookup_first_n_phrases(&self, syllables: &dyn SyllableSlice, first: usize) -> Vec<Phrase> {
+        let syllables = syllables.as_slice().into_owned();
+        let mut phrases = self
+            .root
+            .get(&syllables)
+            .map(|node| node.phrases.clone())
+            .unwrap_or_default();
+        phrases.sort_by(|a, b| b.cmp(a));
+        phrases.truncate(first);
+        phrases
+    }
+
+    fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let prefix = prefix.as_slice().into_owned();
+        let Some(node) = self.root.get(&prefix) else {
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        let mut path = prefix;
+        node.collect_entries(&mut path, &mut entries);
+        entries.sort_by(|a, b| b.1.cmp(&a.1));
+        entries.truncate(first);
+        entries
+    }
+
+    fn entries(&self) -> Option<DictEntries<'_>> {
+        let mut entries = Vec::new();
+        self.root.collect_entries(&mut Vec::new(), &mut entries);
+        entries.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.as_str().cmp(b.1.as_str())));
+        Some(Box::new(entries.into_iter()))
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.info.clone()
+    }
+
+    fn reopen(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), DictionaryUpdateError> {
+        Ok(())
+    }
+
+    fn add_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase: Phrase,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+
+    fn update_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase: Phrase,
+        _user_freq: u32,
+        _time: u64,
+        _prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+
+    fn remove_phrase(
+        &mut self,
+        _syllables: &dyn SyllableSlice,
+        _phrase_str: &str,
+    ) -> Result<(), DictionaryUpdateError> {
+        Err(read_only_error())
+    }
+}
+
+/// Builds a [`TrieDictionary`] file from a sequence of phrase insertions.
+#[derive(Debug, Default)]
+pub struct TrieDictionaryBuilder {
+    info: DictionaryInfo,
+    entries: Vec<(Vec<Syllable>, Phrase)>,
+    bigrams: Vec<(String, Vec<Syllable>, Phrase)>,
+}
+
+impl TrieDictionaryBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> TrieDictionaryBuilder {
+        TrieDictionaryBuilder::default()
+    }
+
+    /// Records that `phrase` (matched by `syllables`) was committed
+    /// immediately after `prev_phrase`, so the built [`TrieDictionary`] can
+    /// answer [`BigramDictionary`] queries for it.
+    pub fn insert_bigram(&mut self, prev_phrase: &str, syllables: &[Syllable], phrase: Phrase) {
+        self.bigrams
+            .push((prev_phrase.to_owned(), syllables.to_vec(), phrase));
+    }
+}
+
+impl DictionaryBuilder for TrieDictionaryBuilder {
+    fn set_info(&mut self, info: DictionaryInfo) -> Result<(), BuildDictionaryError> {
+        self.info = info;
+        Ok(())
+    }
+
+    fn insert(&mut self, syllables: &[Syllable], phrase: Phrase) -> Result<(), BuildDictionaryError> {
+        self.entries.push((syllables.to_vec(), phrase));
+        Ok(())
+    }
+
+    fn build(&mut self, path: &Path) -> Result<(), BuildDictionaryError> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        write_info_line(&mut writer, &self.info)?;
+        for (syllables, phrase) in &self.entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                encode_syllables(syllables),
+                phrase.as_str(),
+                phrase.freq(),
+                phrase
+                    .last_used()
+                    .map(|time| time.to_string())
+                    .unwrap_or_default(),
+            )?;
+        }
+        if !self.bigrams.is_empty() {
+            writeln!(writer, "{BIGRAM_SECTION_MARKER}")?;
+            for (prev_phrase, syllables, phrase) in &self.bigrams {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}",
+                    prev_phrase,
+                    encode_syllables(syllables),
+                    phrase.as_str(),
+                    phrase.freq(),
+                )?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
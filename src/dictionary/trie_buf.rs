@@ -1,9 +1,12 @@
 use std::{
     borrow::Cow,
     cmp,
-    collections::{btree_map::Entry, BTreeMap, BTreeSet},
-    io, iter,
-    path::PathBuf,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
+    fs,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    iter,
+    path::{Path, PathBuf},
     thread::{self, JoinHandle},
 };
 
@@ -12,8 +15,9 @@ use log::error;
 use crate::zhuyin::{Syllable, SyllableSlice};
 
 use super::{
-    BuildDictionaryError, Dictionary, DictionaryBuilder, DictionaryInfo, Entries, Phrase,
-    TrieDictionary, TrieDictionaryBuilder, UpdateDictionaryError,
+    kv::{decode_syllables, encode_syllables},
+    BigramDictionary, BuildDictionaryError, DictEntries, Dictionary, DictionaryBuilder,
+    DictionaryInfo, DictionaryUpdateError, Phrase, TrieDictionary, TrieDictionaryBuilder,
 };
 
 #[derive(Debug)]
@@ -22,25 +26,129 @@ pub struct TrieBufDictionary {
     trie: Option<TrieDictionary>,
     btree: BTreeMap<PhraseKey, (u32, u64)>,
     graveyard: BTreeSet<PhraseKey>,
-    join_handle: Option<JoinHandle<Result<TrieDictionary, UpdateDictionaryError>>>,
+    /// Records staged since the last checkpoint and not yet appended to
+    /// the on-disk log, keyed the same way as `btree`.
+    pending_adds: Vec<(PhraseKey, u32, u64)>,
+    /// Tombstones staged since the last checkpoint and not yet appended to
+    /// the on-disk log.
+    pending_removes: Vec<PhraseKey>,
+    /// Bigram counts learned at runtime through `update_phrase`'s
+    /// `prev_phrase`, keyed the same way the persisted trie's bigram
+    /// section is, that haven't been folded into `trie` by a compaction.
+    /// Overrides the persisted trie's count for the same key.
+    bigrams: BTreeMap<BigramKey, u32>,
+    /// Bigram records staged since the last checkpoint and not yet
+    /// appended to the on-disk log, keyed the same way as `bigrams`.
+    pending_bigrams: Vec<(BigramKey, u32)>,
+    /// Total bytes of append records written to the on-disk log so far.
+    log_total_bytes: u64,
+    /// Bytes of those records that have since been superseded by a later
+    /// record for the same key, and are therefore dead weight.
+    log_unreachable_bytes: u64,
+    /// Byte length of the most recent live log record for each key, used
+    /// to track how much of the log `log_unreachable_bytes` covers.
+    log_offsets: HashMap<PhraseKey, u64>,
+    /// Stack of snapshots taken by `begin_transaction`/`set_savepoint`,
+    /// oldest first. Empty when no transaction is in progress.
+    transaction_stack: Vec<Savepoint>,
+    join_handle: Option<JoinHandle<Result<TrieDictionary, DictionaryUpdateError>>>,
     dirty: bool,
 }
 
 type PhraseKey = (Cow<'static, [Syllable]>, Cow<'static, str>);
 
+/// Key for a learned bigram count: the preceding phrase, paired with the
+/// `(syllables, phrase)` committed immediately after it.
+type BigramKey = (Cow<'static, str>, PhraseKey);
+
+/// A snapshot of the staging buffer taken at `begin_transaction` or a named
+/// `set_savepoint`, used to roll the buffer back without touching the
+/// persisted trie.
+#[derive(Debug, Clone)]
+struct Savepoint {
+    name: Option<String>,
+    btree: BTreeMap<PhraseKey, (u32, u64)>,
+    graveyard: BTreeSet<PhraseKey>,
+    pending_adds: Vec<(PhraseKey, u32, u64)>,
+    pending_removes: Vec<PhraseKey>,
+    bigrams: BTreeMap<BigramKey, u32>,
+    pending_bigrams: Vec<(BigramKey, u32)>,
+    /// `dirty` at the time this savepoint was taken, so restoring it also
+    /// restores whether a checkpoint is owed -- a transaction that's
+    /// rolled back shouldn't leave the dictionary looking dirtier (or
+    /// cleaner) than it was before the transaction started.
+    dirty: bool,
+}
+
 const MIN_PHRASE: &str = "";
 const MAX_PHRASE: &str = "\u{10FFFF}";
 
+/// At or above this fraction of superseded bytes in the append log, `checkpoint`
+/// performs a full trie rebuild (compaction) instead of appending another
+/// delta, bounding how large the log file can grow.
+const COMPACTION_RATIO_THRESHOLD: f64 = 0.5;
+
+/// At or above this many total bytes in the append log, `checkpoint` compacts
+/// regardless of `COMPACTION_RATIO_THRESHOLD`.
+///
+/// A workload that only ever inserts distinct keys never supersedes a
+/// prior record, so `log_unreachable_bytes` stays at 0 and the ratio
+/// check alone would let the log grow without bound. This is the backstop
+/// for that case.
+const COMPACTION_SIZE_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+fn log_path(path: &Path) -> PathBuf {
+    path.with_extension("log")
+}
+
+/// Sibling path a compaction builds the new trie at before atomically
+/// renaming it over `path`, so a crash mid-build never truncates or
+/// corrupts the dictionary that is actually in use.
+fn compaction_tmp_path(path: &Path) -> PathBuf {
+    path.with_extension("tmp")
+}
+
 impl TrieBufDictionary {
+    /// Opens the trie at `path`, recovering from a checkpoint that crashed
+    /// between building the temporary file and renaming it into place.
+    ///
+    /// A leftover `compaction_tmp_path` next to a `path` that still opens
+    /// fine is simply stale (the checkpoint it belongs to either never
+    /// finished building or was superseded) and gets removed. If `path`
+    /// itself fails to open and a temp file is present, the crash must have
+    /// happened after the temp file was fsynced but before the rename
+    /// completed, so the rename is finished here instead of surfacing an
+    /// error.
+    fn open_with_recovery(path: &Path) -> io::Result<TrieDictionary> {
+        let tmp_path = compaction_tmp_path(path);
+        match TrieDictionary::open(path) {
+            Ok(trie) => {
+                let _ = fs::remove_file(&tmp_path);
+                Ok(trie)
+            }
+            Err(err) => {
+                if !tmp_path.exists() {
+                    return Err(err);
+                }
+                fs::rename(&tmp_path, path)?;
+                TrieDictionary::open(path)
+            }
+        }
+    }
+
     pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<TrieBufDictionary> {
         let path = path.into();
         if !path.exists() {
             let info = DictionaryInfo {
-                name: "我的詞庫".to_string(),
-                copyright: "Unknown".to_string(),
-                license: "Unknown".to_string(),
-                version: "0.0.0".to_string(),
-                software: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+                name: Some("我的詞庫".to_string()),
+                copyright: Some("Unknown".to_string()),
+                license: Some("Unknown".to_string()),
+                version: Some("0.0.0".to_string()),
+                software: Some(format!(
+                    "{} {}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                )),
             };
             let mut builder = TrieDictionaryBuilder::new();
             builder
@@ -50,15 +158,25 @@ impl TrieBufDictionary {
                 .build(&path)
                 .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
         }
-        let trie = TrieDictionary::open(&path)?;
-        Ok(TrieBufDictionary {
+        let trie = Self::open_with_recovery(&path)?;
+        let mut dict = TrieBufDictionary {
             path,
             trie: Some(trie),
             btree: BTreeMap::new(),
             graveyard: BTreeSet::new(),
+            pending_adds: Vec::new(),
+            pending_removes: Vec::new(),
+            bigrams: BTreeMap::new(),
+            pending_bigrams: Vec::new(),
+            log_total_bytes: 0,
+            log_unreachable_bytes: 0,
+            log_offsets: HashMap::new(),
+            transaction_stack: Vec::new(),
             join_handle: None,
             dirty: false,
-        })
+        };
+        dict.replay_log()?;
+        Ok(dict)
     }
 
     pub fn new_in_memory() -> TrieBufDictionary {
@@ -67,11 +185,96 @@ impl TrieBufDictionary {
             trie: None,
             btree: BTreeMap::new(),
             graveyard: BTreeSet::new(),
+            pending_adds: Vec::new(),
+            pending_removes: Vec::new(),
+            bigrams: BTreeMap::new(),
+            pending_bigrams: Vec::new(),
+            log_total_bytes: 0,
+            log_unreachable_bytes: 0,
+            log_offsets: HashMap::new(),
+            transaction_stack: Vec::new(),
             join_handle: None,
             dirty: false,
         }
     }
 
+    /// Replays the on-disk append log over the freshly opened base trie,
+    /// reconstructing the staged `btree`/`graveyard` state left behind by
+    /// the last append-only `checkpoint`.
+    ///
+    /// A single record that fails to decode (e.g. a line truncated by a
+    /// crash mid-write, or one left over from an earlier on-disk encoding)
+    /// is skipped rather than treated as fatal, so one corrupt record
+    /// doesn't take the rest of the dictionary down with it.
+    fn replay_log(&mut self) -> io::Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let Ok(file) = File::open(log_path(&self.path)) else {
+            return Ok(());
+        };
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                // Not valid UTF-8, e.g. a multi-byte character cut short.
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => continue,
+                Err(err) => return Err(err),
+            };
+            if self.apply_log_line(&line).is_err() {
+                continue;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_log_line(&mut self, line: &str) -> io::Result<()> {
+        if line.is_empty() {
+            return Ok(());
+        }
+        let mut fields = line.split('\t');
+        let tag = fields.next().unwrap_or_default();
+
+        if tag == "B" {
+            // Bigram records have a different shape (`prev_phrase` first),
+            // so they're decoded on their own rather than sharing the
+            // syllables/phrase parsing the "A"/"D" tags below use.
+            let prev_phrase = fields.next().unwrap_or_default().to_owned();
+            let syllables = decode_syllables(fields.next().unwrap_or_default())?;
+            let phrase_str = fields.next().unwrap_or_default().to_owned();
+            let freq: u32 = fields.next().unwrap_or_default().parse().unwrap_or(0);
+            let key: BigramKey = (Cow::from(prev_phrase), (Cow::from(syllables), Cow::from(phrase_str)));
+            self.bigrams.insert(key, freq);
+            self.log_total_bytes += line.len() as u64 + 1;
+            return Ok(());
+        }
+
+        let syllables = decode_syllables(fields.next().unwrap_or_default())?;
+        let phrase_str = fields.next().unwrap_or_default().to_owned();
+        let key: PhraseKey = (Cow::from(syllables), Cow::from(phrase_str));
+
+        // `lines()` strips the trailing newline; account for it here so
+        // `log_total_bytes` matches what was actually written to disk.
+        let record_len = line.len() as u64 + 1;
+        if let Some(prev_len) = self.log_offsets.insert(key.clone(), record_len) {
+            self.log_unreachable_bytes += prev_len;
+        }
+        self.log_total_bytes += record_len;
+
+        match tag {
+            "A" => {
+                let freq = fields.next().unwrap_or_default().parse().unwrap_or(0);
+                let last_used = fields.next().unwrap_or_default().parse().unwrap_or(0);
+                self.btree.insert(key, (freq, last_used));
+            }
+            "D" => {
+                self.btree.remove(&key);
+                self.graveyard.insert(key);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     pub(crate) fn entries_iter_for<'a>(
         &'a self,
         syllables: &'a dyn SyllableSlice,
@@ -100,7 +303,11 @@ impl TrieBufDictionary {
     }
 
     pub(crate) fn entries_iter(&self) -> impl Iterator<Item = (Vec<Syllable>, Phrase)> + '_ {
-        let mut trie_iter = self.trie.iter().flat_map(|trie| trie.entries()).peekable();
+        let mut trie_iter = self
+            .trie
+            .iter()
+            .flat_map(|trie| trie.entries().into_iter().flatten())
+            .peekable();
         let mut btree_iter = self
             .btree
             .iter()
@@ -169,30 +376,98 @@ impl TrieBufDictionary {
         phrases
     }
 
-    pub(crate) fn entries(&self) -> Entries<'_> {
-        Box::new(self.entries_iter())
+    pub(crate) fn entries(&self) -> Option<DictEntries<'_>> {
+        Some(Box::new(self.entries_iter()))
+    }
+
+    /// Iterates every entry whose syllable key starts with `prefix`,
+    /// merging the base trie and the staging buffer the same way
+    /// `entries_iter_for` does for exact matches, with the graveyard
+    /// filter applied.
+    pub(crate) fn entries_iter_for_prefix<'a>(
+        &'a self,
+        prefix: &'a dyn SyllableSlice,
+    ) -> impl Iterator<Item = (Vec<Syllable>, Phrase)> + 'a {
+        let prefix = prefix.as_slice().into_owned();
+        let min_key = (Cow::from(prefix.clone()), Cow::from(MIN_PHRASE));
+        let prefix_for_store = prefix.clone();
+        let prefix_for_btree = prefix.clone();
+        let store_iter = self
+            .trie
+            .iter()
+            .flat_map(|trie| trie.entries().into_iter().flatten())
+            .filter(move |(syllables, _)| syllables.starts_with(&prefix_for_store));
+        let btree_iter = self
+            .btree
+            .range(min_key..)
+            .take_while(move |(key, _)| key.0.starts_with(&prefix_for_btree))
+            .map(|(key, value)| {
+                (
+                    key.0.clone().into_owned(),
+                    Phrase {
+                        phrase: key.1.clone().into(),
+                        freq: value.0,
+                        last_used: Some(value.1),
+                    },
+                )
+            });
+
+        store_iter.chain(btree_iter).filter(move |(syllables, phrase)| {
+            !self
+                .graveyard
+                .contains(&(Cow::from(syllables.clone()), Cow::from(phrase.as_str())))
+        })
+    }
+
+    /// Returns the first N phrases whose syllable key begins with
+    /// `prefix`, ordered by frequency.
+    pub(crate) fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        let mut sort_map = BTreeMap::new();
+        let mut results: Vec<(Vec<Syllable>, Phrase)> = Vec::new();
+
+        for (syllables, phrase) in self.entries_iter_for_prefix(prefix) {
+            match sort_map.entry((syllables.clone(), phrase.to_string())) {
+                Entry::Occupied(entry) => {
+                    let index = *entry.get();
+                    if phrase.freq() > results[index].1.freq() {
+                        results[index].1 = phrase;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(results.len());
+                    results.push((syllables, phrase));
+                }
+            }
+        }
+        results.sort_by(|a, b| b.1.cmp(&a.1));
+        results.truncate(first);
+        results
     }
 
     pub(crate) fn add_phrase(
         &mut self,
         syllables: &dyn SyllableSlice,
         phrase: Phrase,
-    ) -> Result<(), UpdateDictionaryError> {
+    ) -> Result<(), DictionaryUpdateError> {
         let syllable_slice = syllables.as_slice();
         if self
             .entries_iter_for(&syllable_slice.as_ref())
             .any(|ph| ph.as_str() == phrase.as_str())
         {
-            return Err(UpdateDictionaryError { source: None });
+            return Err(DictionaryUpdateError { source: None });
         }
 
-        self.btree.insert(
-            (
-                Cow::from(syllable_slice.into_owned()),
-                Cow::from(phrase.phrase.into_string()),
-            ),
-            (phrase.freq, phrase.last_used.unwrap_or_default()),
+        let key = (
+            Cow::from(syllable_slice.into_owned()),
+            Cow::from(phrase.phrase.into_string()),
         );
+        let value = (phrase.freq, phrase.last_used.unwrap_or_default());
+        self.btree.insert(key.clone(), value);
+        self.pending_adds.push((key, value.0, value.1));
         self.dirty = true;
 
         Ok(())
@@ -204,35 +479,82 @@ impl TrieBufDictionary {
         phrase: Phrase,
         user_freq: u32,
         time: u64,
-    ) -> Result<(), UpdateDictionaryError> {
-        self.btree.insert(
-            (
-                Cow::from(syllables.as_slice().into_owned()),
-                Cow::from(phrase.phrase.into_string()),
-            ),
-            (user_freq, time),
+        prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        if let Some(prev_phrase) = prev_phrase {
+            self.record_bigram(prev_phrase, syllables, phrase.as_str());
+        }
+
+        let key = (
+            Cow::from(syllables.as_slice().into_owned()),
+            Cow::from(phrase.phrase.into_string()),
         );
+        self.btree.insert(key.clone(), (user_freq, time));
+        self.pending_adds.push((key, user_freq, time));
         self.dirty = true;
 
         Ok(())
     }
 
+    /// Merges the persisted trie's bigram counts for `prev_phrase` with the
+    /// staged `bigrams`, the latter overriding the former on a matching
+    /// `(syllables, phrase)` key.
+    fn merged_bigram_entries(&self, prev_phrase: &str) -> HashMap<(Vec<Syllable>, String), u32> {
+        let mut merged: HashMap<(Vec<Syllable>, String), u32> = self
+            .trie
+            .as_ref()
+            .map(|trie| trie.bigram_entries(prev_phrase))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(syllables, phrase)| ((syllables, phrase.as_str().to_owned()), phrase.freq()))
+            .collect();
+
+        for ((prev, key), freq) in &self.bigrams {
+            if prev.as_ref() != prev_phrase {
+                continue;
+            }
+            merged.insert((key.0.clone().into_owned(), key.1.clone().into_owned()), *freq);
+        }
+        merged
+    }
+
+    /// Records that `phrase` (matched by `syllables`) was just committed
+    /// immediately after `prev_phrase`, incrementing its learned bigram
+    /// count for both staging and the next append to the on-disk log.
+    fn record_bigram(&mut self, prev_phrase: &str, syllables: &dyn SyllableSlice, phrase_str: &str) {
+        let syllables = syllables.as_slice().into_owned();
+        let current = self
+            .merged_bigram_entries(prev_phrase)
+            .get(&(syllables.clone(), phrase_str.to_owned()))
+            .copied()
+            .unwrap_or(0);
+        let freq = current + 1;
+
+        let key: BigramKey = (
+            Cow::from(prev_phrase.to_owned()),
+            (Cow::from(syllables), Cow::from(phrase_str.to_owned())),
+        );
+        self.bigrams.insert(key.clone(), freq);
+        self.pending_bigrams.push((key, freq));
+        self.dirty = true;
+    }
+
     pub(crate) fn remove_phrase(
         &mut self,
         syllables: &dyn SyllableSlice,
         phrase_str: &str,
-    ) -> Result<(), UpdateDictionaryError> {
+    ) -> Result<(), DictionaryUpdateError> {
         let syllable_slice = Cow::from(syllables.as_slice().into_owned());
-        self.btree
-            .remove(&(syllable_slice.clone(), Cow::from(phrase_str.to_owned())));
-        self.graveyard
-            .insert((syllable_slice, phrase_str.to_owned().into()));
+        let key: PhraseKey = (syllable_slice, Cow::from(phrase_str.to_owned()));
+        self.btree.remove(&key);
+        self.graveyard.insert(key.clone());
+        self.pending_removes.push(key);
         self.dirty = true;
 
         Ok(())
     }
 
-    pub(crate) fn sync(&mut self) -> Result<(), UpdateDictionaryError> {
+    pub(crate) fn sync(&mut self) -> Result<(), DictionaryUpdateError> {
         if let Some(join_handle) = self.join_handle.take() {
             if !join_handle.is_finished() {
                 // Wait until previous sync is finished.
@@ -245,52 +567,326 @@ impl TrieBufDictionary {
                     return Ok(());
                 }
                 self.trie = Some(trie);
-                self.btree.clear();
-                self.graveyard.clear();
+                self.clear_log_state();
             } else {
                 error!("[!] Failed to write updated user dictionary due to error.");
             }
-        } else {
+        } else if !self.dirty {
             // TODO: reduce reading
             if !self.path.as_os_str().is_empty() {
                 self.trie = Some(TrieDictionary::open(&self.path)?);
+                self.clear_log_state();
+                self.replay_log()?;
             }
+        } else {
+            // Staged edits haven't been appended to the on-disk log yet
+            // (no flush/checkpoint has run): reloading from disk now would
+            // throw them away without ever having persisted them. Leave
+            // the staging buffer as-is; the next flush() will write it out.
         }
         Ok(())
     }
 
+    /// Clears the staged `btree`/`graveyard` state and append-log
+    /// bookkeeping, leaving only what the current `trie` already knows.
+    fn clear_log_state(&mut self) {
+        self.btree.clear();
+        self.graveyard.clear();
+        self.pending_adds.clear();
+        self.pending_removes.clear();
+        self.bigrams.clear();
+        self.pending_bigrams.clear();
+        self.log_total_bytes = 0;
+        self.log_unreachable_bytes = 0;
+        self.log_offsets.clear();
+    }
+
+    /// Appends the pending `btree`/`graveyard` deltas since the last
+    /// checkpoint to the on-disk log, without touching the base trie.
+    fn append_pending_records(&mut self) -> io::Result<()> {
+        if self.pending_adds.is_empty()
+            && self.pending_removes.is_empty()
+            && self.pending_bigrams.is_empty()
+        {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(&self.path))?;
+        for (key, freq, last_used) in self.pending_adds.drain(..) {
+            let line = format!(
+                "A\t{}\t{}\t{}\t{}\n",
+                encode_syllables(&key.0),
+                key.1,
+                freq,
+                last_used
+            );
+            file.write_all(line.as_bytes())?;
+            let len = line.len() as u64;
+            if let Some(prev_len) = self.log_offsets.insert(key, len) {
+                self.log_unreachable_bytes += prev_len;
+            }
+            self.log_total_bytes += len;
+        }
+        for key in self.pending_removes.drain(..) {
+            let line = format!("D\t{}\t{}\n", encode_syllables(&key.0), key.1);
+            file.write_all(line.as_bytes())?;
+            let len = line.len() as u64;
+            if let Some(prev_len) = self.log_offsets.insert(key, len) {
+                self.log_unreachable_bytes += prev_len;
+            }
+            self.log_total_bytes += len;
+        }
+        // Bigram records aren't tracked in `log_offsets`/`log_unreachable_bytes`
+        // -- they're few compared to phrase edits, and `log_total_bytes`
+        // alone is still enough for `COMPACTION_SIZE_THRESHOLD` to bound
+        // the log's growth.
+        for (key, freq) in self.pending_bigrams.drain(..) {
+            let line = format!(
+                "B\t{}\t{}\t{}\t{}\n",
+                key.0,
+                encode_syllables(&key.1 .0),
+                key.1 .1,
+                freq
+            );
+            file.write_all(line.as_bytes())?;
+            self.log_total_bytes += line.len() as u64;
+        }
+        file.sync_all()
+    }
+
     pub(crate) fn checkpoint(&mut self) {
         if self.join_handle.is_some() || self.trie.is_none() || !self.dirty {
             // Don't need to checkpoint in memory or clean dictionary.
             // Wait until previous checkpoint result is handled.
             return;
         }
+        if !self.transaction_stack.is_empty() {
+            // Don't persist a transaction that hasn't been committed yet.
+            return;
+        }
+        if self.path.as_os_str().is_empty() {
+            self.dirty = false;
+            return;
+        }
+
+        let unreachable_ratio = if self.log_total_bytes == 0 {
+            0.0
+        } else {
+            self.log_unreachable_bytes as f64 / self.log_total_bytes as f64
+        };
+        if unreachable_ratio < COMPACTION_RATIO_THRESHOLD
+            && self.log_total_bytes < COMPACTION_SIZE_THRESHOLD
+        {
+            // The append log is still mostly live data: cheaply append the
+            // pending deltas instead of rebuilding the whole trie.
+            if let Err(err) = self.append_pending_records() {
+                error!("[!] Failed to append pending user dictionary records: {err}");
+                return;
+            }
+            self.dirty = false;
+            return;
+        }
+
         let snapshot = TrieBufDictionary {
             path: self.path.clone(),
             trie: self.trie.clone(),
             btree: self.btree.clone(),
             graveyard: self.graveyard.clone(),
+            pending_adds: Vec::new(),
+            pending_removes: Vec::new(),
+            bigrams: self.bigrams.clone(),
+            pending_bigrams: Vec::new(),
+            log_total_bytes: 0,
+            log_unreachable_bytes: 0,
+            log_offsets: HashMap::new(),
+            transaction_stack: Vec::new(),
             join_handle: None,
             dirty: false,
         };
         self.join_handle = Some(thread::spawn(move || {
             let mut builder = TrieDictionaryBuilder::new();
             builder.set_info(snapshot.about())?;
-            for (syllables, phrase) in snapshot.entries() {
+            for (syllables, phrase) in snapshot.entries().into_iter().flatten() {
                 builder.insert(&syllables, phrase)?;
             }
-            builder.build(&snapshot.path)?;
-            TrieDictionary::open(&snapshot.path).map_err(|err| UpdateDictionaryError {
+            // Fold the staged bigram counts in on top of whatever the base
+            // trie already had, the same staged-overrides-persisted rule
+            // `merged_bigram_entries` uses, so a rebuild never loses counts
+            // learned since the last compaction.
+            let mut merged_bigrams: HashMap<(String, Vec<Syllable>, String), u32> = snapshot
+                .trie
+                .as_ref()
+                .map(|trie| trie.all_bigrams())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(prev_phrase, syllables, phrase)| {
+                    ((prev_phrase, syllables, phrase.as_str().to_owned()), phrase.freq())
+                })
+                .collect();
+            for ((prev_phrase, key), freq) in &snapshot.bigrams {
+                merged_bigrams.insert(
+                    (
+                        prev_phrase.clone().into_owned(),
+                        key.0.clone().into_owned(),
+                        key.1.clone().into_owned(),
+                    ),
+                    *freq,
+                );
+            }
+            for ((prev_phrase, syllables, phrase_str), freq) in merged_bigrams {
+                builder.insert_bigram(&prev_phrase, &syllables, Phrase::new(phrase_str, freq));
+            }
+            // Build at a sibling temp file and only replace the live
+            // dictionary once it is known-good and durably on disk, so a
+            // crash or power loss mid-build leaves the previous file
+            // untouched instead of truncated.
+            let tmp_path = compaction_tmp_path(&snapshot.path);
+            builder.build(&tmp_path)?;
+            File::open(&tmp_path)?.sync_all()?;
+            fs::rename(&tmp_path, &snapshot.path)?;
+            // The rename itself still needs to be fsynced: on some
+            // filesystems, a crash right after a rename can lose the
+            // directory entry it just wrote even though the file's own
+            // contents were fsynced above.
+            if let Some(parent) = snapshot.path.parent() {
+                if let Ok(dir) = File::open(parent) {
+                    let _ = dir.sync_all();
+                }
+            }
+            // The compacted trie now contains everything the log recorded.
+            let _ = fs::remove_file(log_path(&snapshot.path));
+            TrieDictionary::open(&snapshot.path).map_err(|err| DictionaryUpdateError {
                 source: Some(Box::new(err)),
             })
         }));
         self.dirty = false;
     }
+
+    fn snapshot_savepoint(&self, name: Option<String>) -> Savepoint {
+        Savepoint {
+            name,
+            btree: self.btree.clone(),
+            graveyard: self.graveyard.clone(),
+            pending_adds: self.pending_adds.clone(),
+            pending_removes: self.pending_removes.clone(),
+            bigrams: self.bigrams.clone(),
+            pending_bigrams: self.pending_bigrams.clone(),
+            dirty: self.dirty,
+        }
+    }
+
+    fn restore_savepoint(&mut self, savepoint: &Savepoint) {
+        self.btree = savepoint.btree.clone();
+        self.graveyard = savepoint.graveyard.clone();
+        self.pending_adds = savepoint.pending_adds.clone();
+        self.pending_removes = savepoint.pending_removes.clone();
+        self.bigrams = savepoint.bigrams.clone();
+        self.pending_bigrams = savepoint.pending_bigrams.clone();
+        self.dirty = savepoint.dirty;
+    }
+
+    /// Begins a transaction, returning a guard through which the staged
+    /// `add_phrase`/`update_phrase`/`remove_phrase` calls made during it can
+    /// be grouped, checkpointed to named savepoints, and rolled back as a
+    /// whole (e.g. after a failed bulk import) without ever touching the
+    /// persisted trie.
+    ///
+    /// Dropping the guard without calling [`TrieBufTransaction::commit`]
+    /// rolls back to the state at the start of the transaction.
+    pub fn begin_transaction(&mut self) -> TrieBufTransaction<'_> {
+        let base = self.snapshot_savepoint(None);
+        self.transaction_stack.push(base);
+        TrieBufTransaction {
+            dict: self,
+            resolved: false,
+        }
+    }
+}
+
+/// A transaction over a [`TrieBufDictionary`]'s staging buffer, returned by
+/// [`TrieBufDictionary::begin_transaction`].
+#[derive(Debug)]
+pub struct TrieBufTransaction<'a> {
+    dict: &'a mut TrieBufDictionary,
+    resolved: bool,
+}
+
+impl TrieBufTransaction<'_> {
+    /// Records a named point to roll back to later, capturing the current
+    /// state of the staging buffer.
+    pub fn set_savepoint(&mut self, name: impl Into<String>) {
+        let savepoint = self.dict.snapshot_savepoint(Some(name.into()));
+        self.dict.transaction_stack.push(savepoint);
+    }
+
+    /// Discards every change made since the named savepoint, restoring the
+    /// staging buffer to the state it had when that savepoint was taken.
+    ///
+    /// The savepoint itself is kept, so further changes can be made and
+    /// rolled back to it again. Does nothing if `name` was never set.
+    pub fn rollback_to_savepoint(&mut self, name: &str) {
+        let Some(index) = self
+            .dict
+            .transaction_stack
+            .iter()
+            .rposition(|savepoint| savepoint.name.as_deref() == Some(name))
+        else {
+            return;
+        };
+        self.dict.transaction_stack.truncate(index + 1);
+        let savepoint = self.dict.transaction_stack[index].clone();
+        self.dict.restore_savepoint(&savepoint);
+    }
+
+    /// Discards every change made during this transaction, restoring the
+    /// staging buffer to the state it had before [`TrieBufDictionary::begin_transaction`]
+    /// was called.
+    pub fn rollback(mut self) {
+        if let Some(base) = self.dict.transaction_stack.first().cloned() {
+            self.dict.restore_savepoint(&base);
+        }
+        self.dict.transaction_stack.clear();
+        self.resolved = true;
+    }
+
+    /// Keeps every change made during this transaction, leaving the
+    /// dictionary dirty for the next [`TrieBufDictionary::checkpoint`].
+    pub fn commit(mut self) {
+        self.dict.transaction_stack.clear();
+        self.resolved = true;
+    }
+}
+
+impl Drop for TrieBufTransaction<'_> {
+    fn drop(&mut self) {
+        if !self.resolved {
+            if let Some(base) = self.dict.transaction_stack.first().cloned() {
+                self.dict.restore_savepoint(&base);
+            }
+            self.dict.transaction_stack.clear();
+        }
+    }
+}
+
+impl std::ops::Deref for TrieBufTransaction<'_> {
+    type Target = TrieBufDictionary;
+
+    fn deref(&self) -> &TrieBufDictionary {
+        self.dict
+    }
 }
 
-impl From<BuildDictionaryError> for UpdateDictionaryError {
+impl std::ops::DerefMut for TrieBufTransaction<'_> {
+    fn deref_mut(&mut self) -> &mut TrieBufDictionary {
+        self.dict
+    }
+}
+
+impl From<BuildDictionaryError> for DictionaryUpdateError {
     fn from(value: BuildDictionaryError) -> Self {
-        UpdateDictionaryError {
+        DictionaryUpdateError {
             source: Some(Box::new(value)),
         }
     }
@@ -301,7 +897,15 @@ impl Dictionary for TrieBufDictionary {
         TrieBufDictionary::lookup_first_n_phrases(self, syllables, first)
     }
 
-    fn entries(&self) -> Entries<'_> {
+    fn lookup_phrases_by_prefix(
+        &self,
+        prefix: &dyn SyllableSlice,
+        first: usize,
+    ) -> Vec<(Vec<Syllable>, Phrase)> {
+        TrieBufDictionary::lookup_phrases_by_prefix(self, prefix, first)
+    }
+
+    fn entries(&self) -> Option<DictEntries<'_>> {
         TrieBufDictionary::entries(self)
     }
 
@@ -311,12 +915,12 @@ impl Dictionary for TrieBufDictionary {
             .map_or(DictionaryInfo::default(), |trie| trie.about())
     }
 
-    fn reopen(&mut self) -> Result<(), UpdateDictionaryError> {
+    fn reopen(&mut self) -> Result<(), DictionaryUpdateError> {
         self.sync()?;
         Ok(())
     }
 
-    fn flush(&mut self) -> Result<(), UpdateDictionaryError> {
+    fn flush(&mut self) -> Result<(), DictionaryUpdateError> {
         if self.path.as_os_str().is_empty() {
             return Ok(());
         }
@@ -328,7 +932,7 @@ impl Dictionary for TrieBufDictionary {
         &mut self,
         syllables: &dyn SyllableSlice,
         phrase: Phrase,
-    ) -> Result<(), UpdateDictionaryError> {
+    ) -> Result<(), DictionaryUpdateError> {
         TrieBufDictionary::add_phrase(self, syllables, phrase)
     }
 
@@ -338,8 +942,9 @@ impl Dictionary for TrieBufDictionary {
         phrase: Phrase,
         user_freq: u32,
         time: u64,
-    ) -> Result<(), UpdateDictionaryError> {
-        TrieBufDictionary::update_phrase(self, syllables, phrase, user_freq, time)
+        prev_phrase: Option<&str>,
+    ) -> Result<(), DictionaryUpdateError> {
+        TrieBufDictionary::update_phrase(self, syllables, phrase, user_freq, time, prev_phrase)
     }
 
     fn remove_phrase(
@@ -349,6 +954,44 @@ impl Dictionary for TrieBufDictionary {
     ) -> Result<(), DictionaryUpdateError> {
         TrieBufDictionary::remove_phrase(self, syllables, phrase_str)
     }
+
+    /// Starts a transaction through the same staging-buffer savepoint
+    /// machinery [`TrieBufDictionary::begin_transaction`] uses, so callers
+    /// going through `&dyn Dictionary` get real rollback instead of the
+    /// trait's no-op default.
+    fn begin_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        let base = self.snapshot_savepoint(None);
+        self.transaction_stack.push(base);
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        self.transaction_stack.clear();
+        Ok(())
+    }
+
+    fn abort_transaction(&mut self) -> Result<(), DictionaryUpdateError> {
+        if let Some(base) = self.transaction_stack.first().cloned() {
+            self.restore_savepoint(&base);
+        }
+        self.transaction_stack.clear();
+        Ok(())
+    }
+}
+
+impl BigramDictionary for TrieBufDictionary {
+    fn lookup_bigram(&self, prev_phrase: &str, syllables: &dyn SyllableSlice) -> Vec<Phrase> {
+        let syllables = syllables.as_slice().into_owned();
+        self.merged_bigram_entries(prev_phrase)
+            .into_iter()
+            .filter(|((entry_syllables, _), _)| *entry_syllables == syllables)
+            .map(|((_, phrase), freq)| Phrase::new(phrase, freq))
+            .collect()
+    }
+
+    fn bigram_total(&self, prev_phrase: &str) -> u32 {
+        self.merged_bigram_entries(prev_phrase).values().sum()
+    }
 }
 
 impl<const N: usize> From<[(Vec<Syllable>, Vec<Phrase>); N]> for TrieBufDictionary {
@@ -390,7 +1033,7 @@ mod tests {
             &[syl![Z, TONE4], syl![D, I, AN, TONE3]],
             ("dict", 1, 2).into(),
         )?;
-        assert_eq!("Unknown", info.copyright);
+        assert_eq!(Some("Unknown".to_string()), info.copyright);
         assert_eq!(
             Some(("dict", 1, 2).into()),
             dict.lookup_first_phrase(&[syl![Z, TONE4], syl![D, I, AN, TONE3]])
@@ -413,7 +1056,7 @@ mod tests {
         }
         let dict = TrieBufDictionary::open(file_path)?;
         let info = dict.about();
-        assert_eq!("Unknown", info.copyright);
+        assert_eq!(Some("Unknown".to_string()), info.copyright);
         assert_eq!(
             Some(("dict", 1, 2).into()),
             dict.lookup_first_phrase(&[syl![Z, TONE4], syl![D, I, AN, TONE3]])
@@ -436,7 +1079,71 @@ mod tests {
                 vec![syl![Z, TONE4], syl![D, I, AN, TONE3]],
                 Phrase::from(("dict", 1, 2))
             )],
-            dict.entries().collect::<Vec<_>>()
+            dict.entries().into_iter().flatten().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_rollback_discards_changes() -> Result<(), Box<dyn Error>> {
+        let mut dict = TrieBufDictionary::new_in_memory();
+        dict.add_phrase(&[syl![Z, TONE4]], ("字", 1).into())?;
+
+        let mut txn = dict.begin_transaction();
+        txn.add_phrase(&[syl![Z, TONE4]], ("自", 1).into())?;
+        txn.set_savepoint("after_first_add");
+        txn.add_phrase(&[syl![Z, TONE4]], ("漬", 1).into())?;
+        txn.rollback_to_savepoint("after_first_add");
+        assert_eq!(2, txn.lookup_all_phrases(&[syl![Z, TONE4]]).len());
+        txn.rollback();
+
+        assert_eq!(1, dict.lookup_all_phrases(&[syl![Z, TONE4]]).len());
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_phrases_by_prefix_finds_longer_continuations() -> Result<(), Box<dyn Error>> {
+        let mut dict = TrieBufDictionary::new_in_memory();
+        dict.add_phrase(&[syl![C, E, TONE4]], ("策", 1).into())?;
+        dict.add_phrase(
+            &[syl![C, E, TONE4], syl![SH, TONE4]],
+            ("測試", 2).into(),
+        )?;
+        dict.add_phrase(&[syl![SH, TONE4]], ("試", 1).into())?;
+
+        let matches = dict.lookup_phrases_by_prefix(&[syl![C, E, TONE4]], 10);
+        assert_eq!(2, matches.len());
+        assert!(matches.iter().any(|(_, phrase)| phrase.as_str() == "策"));
+        assert!(matches.iter().any(|(_, phrase)| phrase.as_str() == "測試"));
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_compacts_through_a_temp_file_and_rename() -> Result<(), Box<dyn Error>> {
+        let tmp_dir = tempfile::tempdir()?;
+        let file_path = tmp_dir.path().join("user.dat");
+        {
+            let mut dict = TrieBufDictionary::open(&file_path)?;
+            dict.add_phrase(&[syl![Z, TONE4]], ("字", 1).into())?;
+            dict.flush()?;
+            // The first update's record supersedes the add's, bringing the
+            // append log's unreachable ratio up to the compaction threshold
+            // (half the log), so the second update's flush compacts.
+            dict.update_phrase(&[syl![Z, TONE4]], ("字", 2).into(), 2, 0, None)?;
+            dict.flush()?;
+            dict.update_phrase(&[syl![Z, TONE4]], ("字", 2).into(), 2, 0, None)?;
+            dict.flush()?;
+            // Dropping here runs the final flush and joins the
+            // compaction thread spawned above.
+        }
+
+        assert!(!super::compaction_tmp_path(&file_path).exists());
+        assert!(!super::log_path(&file_path).exists());
+
+        let dict = TrieBufDictionary::open(&file_path)?;
+        assert_eq!(
+            Some(("字", 2, 0).into()),
+            dict.lookup_first_phrase(&[syl![Z, TONE4]])
         );
         Ok(())
     }